@@ -1,18 +1,16 @@
-use serde::Serialize;
-use std::collections::HashMap;
+use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::{self, Display};
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process;
+use syn::spanned::Spanned;
 use tinytemplate::TinyTemplate;
 
-static TEMPLATE: &'static str = r#"// Copyright (c) 2020 xxx.yyy 
-//
-// SPDX-License-Identifier: Apache-2.0
-//
-// WARNING: This file is auto-generated - DO NOT EDIT!
+static TEMPLATE: &str = r#"{header}
 
 package virtcontainers
 
@@ -20,7 +18,7 @@ import (
     "github.com/prometheus/client_golang/prometheus"
 )
 
-const fcMetricsNS = "kata_firecracker"
+const fcMetricsNS = "{namespace}"
 
 // prometheus metrics Firecracker exposed.
 var (
@@ -44,40 +42,212 @@ func updateFirecrackerMetrics(fm *FirecrackerMetrics) \{
 {{ endfor }}
 "#;
 
+static DEFAULT_HEADER: &str = "// Copyright (c) 2020 xxx.yyy \n\
+//\n\
+// SPDX-License-Identifier: Apache-2.0\n\
+//\n\
+// WARNING: This file is auto-generated - DO NOT EDIT!";
+
+/// User-tunable generation settings, optionally loaded from a `--config`
+/// TOML file. Every field defaults to the values the generator used before
+/// configuration existed, so an absent file changes nothing and a partial
+/// one changes only the fields it sets.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+struct Settings {
+    /// Prometheus namespace, emitted as `const fcMetricsNS`.
+    namespace: String,
+    /// label key name for each `GaugeVec`.
+    label: String,
+    /// copyright/license header prepended to the generated file.
+    header: String,
+    /// extra Rust-type -> Go-type mappings, merged over the built-in ones.
+    type_map: HashMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        let mut type_map = HashMap::new();
+        type_map.insert("SharedMetric".to_string(), "uint64".to_string());
+        Settings {
+            namespace: "kata_firecracker".to_string(),
+            label: "item".to_string(),
+            header: DEFAULT_HEADER.to_string(),
+            type_map,
+        }
+    }
+}
+
+impl Settings {
+    /// Map a Rust type name to its Go equivalent, falling back to the Rust
+    /// name itself when no mapping is configured.
+    fn go_var_type(&self, s: &str) -> String {
+        match self.type_map.get(s) {
+            Some(go_type) => go_type.to_string(),
+            None => s.to_string(),
+        }
+    }
+
+    /// The Go `[]string{...}` label list for a `GaugeVec` declaration. A single
+    /// key keeps the declaration consistent with the `WithLabelValues(...)`
+    /// setter, which only ever supplies the field name as its value.
+    fn label_literal(&self) -> String {
+        format!("\"{}\"", self.label)
+    }
+}
+
 enum GenerateError {
     IncorrectUsage,
     ReadFile(io::Error),
     ParseError(syn::Error),
     RenderError(tinytemplate::error::Error),
+    UnknownTarget(String),
+    CheckFailed(String),
+    ConfigError(toml::de::Error),
+    UnknownModelFormat(String),
+    EmitError(serde_json::Error),
 }
 
 impl Display for GenerateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::GenerateError::*;
         match self {
-            IncorrectUsage => write!(f, "Usage: fc-metrics-generator path/to/filename.rs"),
+            IncorrectUsage => write!(
+                f,
+                "Usage: fc-metrics-generator [--target <name>] [--template <file>] [--check <existing.go>] [--config <config.toml>] [--emit-model json] path/to/filename.rs"
+            ),
             ReadFile(error) => write!(f, "Failed to read file: {}", error),
             ParseError(error) => write!(f, "Failed to parse source file: {}", error),
             RenderError(error) => write!(f, "Failed to render source file: {}", error),
+            UnknownTarget(name) => write!(f, "Unknown target `{}` (known: go-prometheus)", name),
+            CheckFailed(diff) => write!(f, "{}", diff),
+            ConfigError(error) => write!(f, "Failed to parse config file: {}", error),
+            UnknownModelFormat(name) => {
+                write!(f, "Unknown model format `{}` (known: json)", name)
+            }
+            EmitError(error) => write!(f, "Failed to serialize model: {}", error),
         }
     }
 }
 
+#[derive(Serialize)]
 struct RustStruct {
     comments: Vec<String>,
     name: String,
-    // struct_item: syn::ItemStruct,
     fields: Vec<StructField>,
 }
 
+#[derive(Serialize)]
 struct StructField {
     /// var name for golang
     var_name: String,
     /// var type for golang
     var_type: String,
+    /// span of the field's type, used for diagnostics
+    #[serde(skip)]
+    type_span: Span,
     comments: Vec<String>,
 }
 
+/// Keeps the original source text alongside its file name so spans collected
+/// during parsing can be rendered as compiler-style annotated snippets.
+struct SourceMap {
+    filename: String,
+    /// byte offset of the first character of each line
+    line_starts: Vec<usize>,
+    source: String,
+}
+
+impl SourceMap {
+    fn new(filename: String, source: String) -> SourceMap {
+        let mut line_starts = vec![0];
+        for (i, b) in source.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceMap {
+            filename,
+            line_starts,
+            source,
+        }
+    }
+
+    /// Convert a `(line, column)` pair (1-based line, 0-based column, as
+    /// reported by `Span::start`/`Span::end`) into a byte offset over `source`.
+    fn byte_offset(&self, line: usize, column: usize) -> usize {
+        let line_start = self
+            .line_starts
+            .get(line.saturating_sub(1))
+            .copied()
+            .unwrap_or(self.source.len());
+        let line_text = self.source[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or("");
+        // columns are counted in characters, translate back to bytes
+        let byte_in_line = line_text
+            .char_indices()
+            .nth(column)
+            .map(|(i, _)| i)
+            .unwrap_or(line_text.len());
+        line_start + byte_in_line
+    }
+
+    /// Render the line containing `span` with a caret underline under the
+    /// spanned token, headed by `level` and `label`, mimicking rustc's output.
+    /// `level` is the leading severity word (e.g. "warning" or "error").
+    fn render(&self, level: &str, span: Span, label: &str) -> String {
+        let start = span.start();
+        let end = span.end();
+        let line_no = start.line;
+
+        let line_start = self.byte_offset(line_no, 0);
+        let line_end = self.source[line_start..]
+            .find('\n')
+            .map(|i| line_start + i)
+            .unwrap_or(self.source.len());
+        let src_line = &self.source[line_start..line_end];
+
+        // carets span the token on its first line only
+        let caret_start = start.column;
+        let caret_end = if end.line == start.line {
+            end.column
+        } else {
+            src_line.chars().count()
+        };
+        let underline = format!(
+            "{}{}",
+            " ".repeat(caret_start),
+            "^".repeat(caret_end.saturating_sub(caret_start).max(1))
+        );
+
+        let gutter = line_no.to_string();
+        let pad = " ".repeat(gutter.len());
+        format!(
+            "{level}: {label}\n\
+             {pad} --> {file}:{line}:{col}\n\
+             {pad} |\n\
+             {gutter} | {src}\n\
+             {pad} | {underline}\n",
+            level = level,
+            label = label,
+            pad = pad,
+            file = self.filename,
+            line = line_no,
+            col = caret_start + 1,
+            gutter = gutter,
+            src = src_line,
+            underline = underline,
+        )
+    }
+
+    /// Emit a warning-level snippet to stderr.
+    fn warn(&self, span: Span, label: &str) {
+        let _ = write!(io::stderr(), "{}", self.render("warning", span, label));
+    }
+}
+
 fn strip_comment(s: &mut String) -> &mut String {
     // remove the last double quota
     s.pop();
@@ -86,12 +256,12 @@ fn strip_comment(s: &mut String) -> &mut String {
     s
 }
 
-fn json_tag(s: &String) -> String {
+fn json_tag(s: &str) -> String {
     format!("`json:\"{}\"`", s)
 }
 
-fn rust_field_name_to_go(s: &String) -> String {
-    let vv: Vec<String> = s.split('_').map(|x| to_uppercase(&x)).collect::<Vec<_>>();
+fn rust_field_name_to_go(s: &str) -> String {
+    let vv: Vec<String> = s.split('_').map(to_uppercase).collect::<Vec<_>>();
     vv.join("")
 }
 
@@ -111,19 +281,17 @@ fn to_uppercase(s: &str) -> String {
         .collect::<String>()
 }
 
-fn go_var_type(s: &String) -> String {
-    if s == "SharedMetric" {
-        return "uint64".to_string();
-    }
-    return s.to_string();
-}
-
 impl RustStruct {
     fn metric_var_name(&self) -> String {
         to_lowercase(&self.name)
     }
 
-    fn generate_struct_definition_code(&self, vec: &mut Vec<String>, comments: &Vec<String>) {
+    fn generate_struct_definition_code(
+        &self,
+        vec: &mut Vec<String>,
+        comments: &[String],
+        settings: &Settings,
+    ) {
         for c in comments {
             vec.push(format!("// {}", c));
         }
@@ -135,26 +303,33 @@ impl RustStruct {
             vec.push(format!(
                 "    {} {} {}",
                 rust_field_name_to_go(&f.var_name),
-                go_var_type(&f.var_type),
+                settings.go_var_type(&f.var_type),
                 json_tag(&f.var_name)
             ));
         }
-        vec.push(format!("}}"));
+        vec.push("}".to_string());
         vec.push("".to_string());
     }
 
-    fn generate_declare_metric_code(&self, vec: &mut Vec<String>, name: &String, help: &String) {
+    fn generate_declare_metric_code(
+        &self,
+        vec: &mut Vec<String>,
+        name: &str,
+        help: &str,
+        settings: &Settings,
+    ) {
         vec.push(format!(
             r#"{} = prometheus.NewGaugeVec(prometheus.GaugeOpts{{
             Namespace: fcMetricsNS,
             Name:      "{}",
             Help:      "{}",
         }},
-            []string{{"item"}},
+            []string{{{}}},
         )"#,
             self.metric_var_name(),
             name,
-            help
+            help,
+            settings.label_literal(),
         ));
         vec.push("".to_string());
     }
@@ -165,20 +340,6 @@ impl RustStruct {
             self.metric_var_name()
         ))
     }
-
-    fn generate_set_values_code(&self, vec: &mut Vec<String>, field_name: &String) {
-        vec.push(format!("    // set metrics for {}", self.name));
-        for f in &self.fields {
-            vec.push(format!(
-                "    {}.WithLabelValues(\"{}\").Set(float64(fm.{}.{}))",
-                self.metric_var_name(),
-                f.var_name,
-                field_name,
-                rust_field_name_to_go(&f.var_name)
-            ));
-        }
-        vec.push("".to_string());
-    }
 }
 
 fn main() {
@@ -188,175 +349,537 @@ fn main() {
     }
 }
 
-fn try_main() -> Result<(), GenerateError> {
+/// Command-line options controlling the input file and output backend.
+struct Options {
+    filepath: PathBuf,
+    /// name of a built-in target, e.g. `go-prometheus`
+    target: String,
+    /// path to a user-supplied `tinytemplate` file (overrides `target`)
+    template: Option<PathBuf>,
+    /// when set, diff the rendered output against this file instead of printing
+    check: Option<PathBuf>,
+    /// optional TOML file overriding the generation [`Settings`]
+    config: Option<PathBuf>,
+    /// when set, emit the serializable [`Model`] in this format instead of code
+    emit_model: Option<String>,
+}
+
+fn parse_options() -> Result<Options, GenerateError> {
     let mut args = env::args_os();
     let _ = args.next();
 
-    let filepath = match (args.next(), args.next()) {
-        (Some(arg), None) => PathBuf::from(arg),
-        _ => return Err(GenerateError::IncorrectUsage),
-    };
+    let mut filepath = None;
+    let mut target = "go-prometheus".to_string();
+    let mut template = None;
+    let mut check = None;
+    let mut config = None;
+    let mut emit_model = None;
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--target") => {
+                let value = args.next().ok_or(GenerateError::IncorrectUsage)?;
+                target = value.to_string_lossy().into_owned();
+            }
+            Some("--template") => {
+                let value = args.next().ok_or(GenerateError::IncorrectUsage)?;
+                template = Some(PathBuf::from(value));
+            }
+            Some("--check") => {
+                let value = args.next().ok_or(GenerateError::IncorrectUsage)?;
+                check = Some(PathBuf::from(value));
+            }
+            Some("--config") => {
+                let value = args.next().ok_or(GenerateError::IncorrectUsage)?;
+                config = Some(PathBuf::from(value));
+            }
+            Some("--emit-model") => {
+                let value = args.next().ok_or(GenerateError::IncorrectUsage)?;
+                emit_model = Some(value.to_string_lossy().into_owned());
+            }
+            _ => {
+                if filepath.is_some() {
+                    return Err(GenerateError::IncorrectUsage);
+                }
+                filepath = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    Ok(Options {
+        filepath: filepath.ok_or(GenerateError::IncorrectUsage)?,
+        target,
+        template,
+        check,
+        config,
+        emit_model,
+    })
+}
+
+/// Load generation [`Settings`] from the `--config` TOML file, or the
+/// built-in defaults when none was given.
+fn load_settings(options: &Options) -> Result<Settings, GenerateError> {
+    match &options.config {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(GenerateError::ReadFile)?;
+            let mut settings: Settings = toml::from_str(&text).map_err(GenerateError::ConfigError)?;
+            // `#[serde(default)]` replaces the whole `[type_map]` table when the
+            // config supplies one, so re-inject the built-in mappings the config
+            // didn't override: custom entries extend rather than drop them.
+            for (k, v) in Settings::default().type_map {
+                settings.type_map.entry(k).or_insert(v);
+            }
+            Ok(settings)
+        }
+        None => Ok(Settings::default()),
+    }
+}
+
+/// Normalize trivial whitespace so the `--check` diff ignores trailing
+/// spaces and trailing blank lines: trim each line's trailing whitespace and
+/// drop empty lines at the end of the file.
+fn normalize_lines(s: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = s.lines().map(|l| l.trim_end()).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+/// Produce a readable line-by-line diff of `expected` (the checked-in file)
+/// against `actual` (freshly rendered), or `None` when they match after
+/// whitespace normalization.
+fn diff_generated(name: &str, expected: &str, actual: &str) -> Option<String> {
+    let expected = normalize_lines(expected);
+    let actual = normalize_lines(actual);
+    if expected == actual {
+        return None;
+    }
+
+    let mut out = format!("{} is out of date; regenerate it:\n", name);
+    let max = expected.len().max(actual.len());
+    for i in 0..max {
+        match (expected.get(i), actual.get(i)) {
+            (a, b) if a == b => {}
+            (a, b) => {
+                if let Some(a) = a {
+                    out.push_str(&format!("-{}\n", a));
+                }
+                if let Some(b) = b {
+                    out.push_str(&format!("+{}\n", b));
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+fn try_main() -> Result<(), GenerateError> {
+    let options = parse_options()?;
+    let settings = load_settings(&options)?;
 
-    let code = fs::read_to_string(&filepath).map_err(GenerateError::ReadFile)?;
+    let code = fs::read_to_string(&options.filepath).map_err(GenerateError::ReadFile)?;
     let syntax = syn::parse_file(&code).map_err(GenerateError::ParseError)?;
 
+    let sm = SourceMap::new(options.filepath.display().to_string(), code);
+
     // parse source file
-    let struct_list = parse_source_code(&syntax);
+    let struct_list = parse_source_code(&syntax, &sm);
+
+    // emit the serializable IR instead of generated code, when asked.
+    if let Some(format) = &options.emit_model {
+        if format != "json" {
+            return Err(GenerateError::UnknownModelFormat(format.clone()));
+        }
+        let model = build_model(&struct_list);
+        let json = serde_json::to_string_pretty(&model).map_err(GenerateError::EmitError)?;
+        println!("{}", json);
+        return Ok(());
+    }
 
-    // parse metrics constructs and generate metrics definitions,
-    // register statements, set statements.
-    let context = parse_source_tree(struct_list);
+    // render to the selected backend, which owns its own statement generation.
+    let target = select_target(&options)?;
+    let rendered = target.render(&struct_list, &settings, &sm)?;
 
-    // render to go source file.
-    render(context).map_err(GenerateError::RenderError)?;
+    // in --check mode, diff against the committed file instead of printing.
+    if let Some(existing) = &options.check {
+        let current = fs::read_to_string(existing).map_err(GenerateError::ReadFile)?;
+        match diff_generated(&existing.display().to_string(), &current, &rendered) {
+            Some(diff) => return Err(GenerateError::CheckFailed(diff)),
+            None => return Ok(()),
+        }
+    }
+
+    println!("{}", rendered);
 
     Ok(())
 }
 
 #[derive(Serialize)]
 struct Context {
+    header: String,
+    namespace: String,
     metrics_var_declare_stmt: Vec<String>,
     metrics_register_stmt: Vec<String>,
     metrics_set_stmt: Vec<String>,
     metrics_struct_declare_stmt: Vec<String>,
 }
 
-fn render(context: Context) -> Result<(), tinytemplate::error::Error> {
-    let mut tt = TinyTemplate::new();
-    tt.add_template("metrics", TEMPLATE)?;
+/// A code-generation backend: given the parsed structs and the generation
+/// [`Settings`], it owns the whole path from model to source text for one
+/// output format — including building whatever statements that format needs.
+///
+/// The Go/Prometheus emitter ships as the default ([`GoPrometheusTarget`]) and
+/// builds its Go-specific `Context` internally; users can supply their own
+/// `tinytemplate` file with `--template` ([`TemplateTarget`]), which is handed
+/// the raw serializable model and settings so it can emit an entirely
+/// different format (OpenMetrics, a Rust client, a JSON schema, ...) rather
+/// than merely re-arranging pre-rendered Go.
+trait Target {
+    fn render(
+        &self,
+        struct_list: &HashMap<String, RustStruct>,
+        settings: &Settings,
+        sm: &SourceMap,
+    ) -> Result<String, GenerateError>;
+}
 
+/// Render a serializable `context` through a `tinytemplate` source string.
+fn render_template<C: Serialize>(
+    template: &str,
+    context: &C,
+) -> Result<String, tinytemplate::error::Error> {
+    let mut tt = TinyTemplate::new();
+    tt.add_template("metrics", template)?;
     tt.set_default_formatter(&tinytemplate::format_unescaped);
+    tt.render("metrics", context)
+}
 
-    let rendered = tt.render("metrics", &context)?;
-    println!("{}", rendered);
+/// The default backend: build the Go/Prometheus statements and render them
+/// through the hard-coded `TEMPLATE`.
+struct GoPrometheusTarget;
+
+impl Target for GoPrometheusTarget {
+    fn render(
+        &self,
+        struct_list: &HashMap<String, RustStruct>,
+        settings: &Settings,
+        sm: &SourceMap,
+    ) -> Result<String, GenerateError> {
+        let context = parse_source_tree(struct_list, sm, settings);
+        render_template(TEMPLATE, &context).map_err(GenerateError::RenderError)
+    }
+}
 
-    Ok(())
+/// Everything a `--template` author can read: the generation settings plus the
+/// serializable model of every parsed struct and its root→child relationships.
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    settings: &'a Settings,
+    model: Model<'a>,
 }
 
-fn parse_source_tree(struct_list: HashMap<String, RustStruct>) -> Context {
-    let mut metrics_var_declare_stmt: Vec<String> = Vec::new();
-    let mut metrics_register_stmt: Vec<String> = Vec::new();
-    let mut metrics_set_stmt: Vec<String> = Vec::new();
-    let mut metrics_struct_declare_stmt: Vec<String> = Vec::new();
+/// A user-supplied `tinytemplate` file loaded at runtime via `--template`.
+/// It renders against the raw model, so the author writes their own
+/// statement-builders for whatever output format they need.
+struct TemplateTarget {
+    template: String,
+}
+
+impl Target for TemplateTarget {
+    fn render(
+        &self,
+        struct_list: &HashMap<String, RustStruct>,
+        settings: &Settings,
+        _sm: &SourceMap,
+    ) -> Result<String, GenerateError> {
+        let context = TemplateContext {
+            settings,
+            model: build_model(struct_list),
+        };
+        render_template(&self.template, &context).map_err(GenerateError::RenderError)
+    }
+}
+
+/// Resolve the `--target`/`--template` options into a concrete backend.
+fn select_target(options: &Options) -> Result<Box<dyn Target>, GenerateError> {
+    if let Some(path) = &options.template {
+        let template = fs::read_to_string(path).map_err(GenerateError::ReadFile)?;
+        return Ok(Box::new(TemplateTarget { template }));
+    }
+    match options.target.as_str() {
+        "go-prometheus" => Ok(Box::new(GoPrometheusTarget)),
+        other => Err(GenerateError::UnknownTarget(other.to_string())),
+    }
+}
+
+/// A serializable intermediate representation of the parsed metric model:
+/// every struct, plus the root→child relationships that `parse_source_tree`
+/// discovers. Downstream tooling (dashboards, docs tables, alternative client
+/// bindings) can consume this instead of re-walking the `syn` AST.
+#[derive(Serialize)]
+struct Model<'a> {
+    /// name of the root struct, when present in the source
+    root: Option<&'a str>,
+    /// every parsed struct, keyed by name
+    structs: &'a HashMap<String, RustStruct>,
+    /// for each struct-typed field on the root, the child it expands to
+    relationships: Vec<Relationship<'a>>,
+}
+
+#[derive(Serialize)]
+struct Relationship<'a> {
+    /// field name on the root struct
+    field: &'a str,
+    /// help text derived from the child struct's doc comments
+    help: String,
+    /// the child metric struct this field expands to
+    metric_struct: &'a str,
+}
+
+const ROOT_STRUCT: &str = "FirecrackerMetrics";
+
+/// Build the serializable [`Model`] from the parsed structs, capturing the
+/// same root→child relationships that `parse_source_tree` walks.
+fn build_model(struct_list: &HashMap<String, RustStruct>) -> Model<'_> {
+    let root = struct_list.get(ROOT_STRUCT);
+    let mut relationships = Vec::new();
+    if let Some(root_struct) = root {
+        for f in &root_struct.fields {
+            if let Some(child) = struct_list.get(&f.var_type) {
+                relationships.push(Relationship {
+                    field: &f.var_name,
+                    help: child.comments.join(" ").trim().to_string(),
+                    metric_struct: &child.name,
+                });
+            }
+        }
+    }
 
-    match struct_list.get(&"FirecrackerMetrics".to_string()) {
+    Model {
+        root: root.map(|_| ROOT_STRUCT),
+        structs: struct_list,
+        relationships,
+    }
+}
+
+/// Mutable state threaded through the recursive descent in
+/// [`MetricEmitter::emit`]: the four output statement buffers plus the two
+/// book-keeping sets. `visited` holds the struct types on the current descent
+/// path and guards against recursive or mutually-referential types; `emitted`
+/// holds the struct types whose type/var/register statements have already been
+/// written so each is declared only once.
+#[derive(Default)]
+struct MetricEmitter {
+    visited: HashSet<String>,
+    emitted: HashSet<String>,
+    var_stmt: Vec<String>,
+    register_stmt: Vec<String>,
+    set_stmt: Vec<String>,
+    struct_stmt: Vec<String>,
+}
+
+impl MetricEmitter {
+    /// Recursively emit the definition, declaration, register and set
+    /// statements for `metric_struct`, which is reached from the root via the
+    /// dotted Go field `path` (e.g. `Parent.Child`). Struct-typed fields at any
+    /// depth are descended into and wired up with their full
+    /// `fm.Parent.Child.Field` accessor.
+    fn emit(
+        &mut self,
+        struct_list: &HashMap<String, RustStruct>,
+        field: &StructField,
+        metric_struct: &RustStruct,
+        path: &str,
+        settings: &Settings,
+    ) {
+        // cycle guard: don't descend into a type already on this path
+        if !self.visited.insert(metric_struct.name.clone()) {
+            return;
+        }
+
+        // type / GaugeVec / register statements are written once per struct type
+        if self.emitted.insert(metric_struct.name.clone()) {
+            metric_struct.generate_struct_definition_code(
+                &mut self.struct_stmt,
+                &field.comments,
+                settings,
+            );
+            let help = metric_struct.comments.join(" ").trim().to_string();
+            metric_struct.generate_declare_metric_code(
+                &mut self.var_stmt,
+                &field.var_name,
+                &help,
+                settings,
+            );
+            metric_struct.generate_register_code(&mut self.register_stmt);
+        }
+
+        self.set_stmt
+            .push(format!("    // set metrics for {}", metric_struct.name));
+        for f in &metric_struct.fields {
+            match struct_list.get(&f.var_type) {
+                Some(child) => {
+                    // struct-typed field: descend, extending the dotted path
+                    let child_path = format!("{}.{}", path, rust_field_name_to_go(&f.var_name));
+                    self.emit(struct_list, f, child, &child_path, settings);
+                }
+                None => {
+                    // scalar field: set it against the full accessor path
+                    self.set_stmt.push(format!(
+                        "    {}.WithLabelValues(\"{}\").Set(float64(fm.{}.{}))",
+                        metric_struct.metric_var_name(),
+                        f.var_name,
+                        path,
+                        rust_field_name_to_go(&f.var_name)
+                    ));
+                }
+            }
+        }
+        self.set_stmt.push("".to_string());
+
+        self.visited.remove(&metric_struct.name);
+    }
+}
+
+fn parse_source_tree(
+    struct_list: &HashMap<String, RustStruct>,
+    sm: &SourceMap,
+    settings: &Settings,
+) -> Context {
+    let mut emitter = MetricEmitter::default();
+
+    match struct_list.get(ROOT_STRUCT) {
         Some(root_struct) => {
             // generate struct for FirecrackerMetrics
             root_struct.generate_struct_definition_code(
-                &mut &mut metrics_struct_declare_stmt,
+                &mut emitter.struct_stmt,
                 &root_struct.comments,
+                settings,
             );
 
             for f in &root_struct.fields {
                 match struct_list.get(&f.var_type) {
                     Some(metric_struct) => {
-                        metric_struct.generate_struct_definition_code(
-                            &mut &mut metrics_struct_declare_stmt,
-                            &f.comments,
-                        );
-                        let help = metric_struct.comments.join(" ").trim().to_string();
-                        metric_struct.generate_declare_metric_code(
-                            &mut metrics_var_declare_stmt,
-                            &f.var_name,
-                            &help,
-                        );
-                        metric_struct.generate_register_code(&mut metrics_register_stmt);
-                        metric_struct.generate_set_values_code(
-                            &mut metrics_set_stmt,
-                            &rust_field_name_to_go(&f.var_name),
+                        let path = rust_field_name_to_go(&f.var_name);
+                        emitter.emit(struct_list, f, metric_struct, &path, settings);
+                    }
+                    _ => {
+                        // the field's type is not a struct defined in this file
+                        sm.warn(
+                            f.type_span,
+                            &format!(
+                                "unknown metric type `{}` — no matching struct found in this file",
+                                f.var_type
+                            ),
                         );
                     }
-                    _ => {}
                 }
             }
         }
-        _ => {}
+        _ => {
+            // no root struct to walk; report it against the file name and
+            // render an otherwise empty context
+            let _ = writeln!(
+                io::stderr(),
+                "error: no `FirecrackerMetrics` root struct found in {}",
+                sm.filename
+            );
+        }
     }
 
     // prepare for render template
     Context {
-        metrics_var_declare_stmt: metrics_var_declare_stmt,
-        metrics_register_stmt: metrics_register_stmt,
-        metrics_set_stmt: metrics_set_stmt,
-        metrics_struct_declare_stmt: metrics_struct_declare_stmt,
+        header: settings.header.clone(),
+        namespace: settings.namespace.clone(),
+        metrics_var_declare_stmt: emitter.var_stmt,
+        metrics_register_stmt: emitter.register_stmt,
+        metrics_set_stmt: emitter.set_stmt,
+        metrics_struct_declare_stmt: emitter.struct_stmt,
     }
 }
 
-fn parse_source_code(syntax: &syn::File) -> HashMap<String, RustStruct> {
+fn parse_source_code(syntax: &syn::File, sm: &SourceMap) -> HashMap<String, RustStruct> {
     let mut struct_list = HashMap::new();
 
     for item in syntax.items.iter() {
-        match item {
-            syn::Item::Struct(struct_item) => {
-                let mut struct_comments = vec![];
-                for attr in &struct_item.attrs {
-                    if &attr.path.segments.first().unwrap().ident.to_string() == "doc" {
-                        let mut c = attr.tokens.to_string();
-                        c = strip_comment(&mut c).to_string();
-                        struct_comments.push(c);
-                    }
+        let syn::Item::Struct(struct_item) = item else {
+            continue;
+        };
+
+        let mut struct_comments = vec![];
+        for attr in &struct_item.attrs {
+            if &attr.path.segments.first().unwrap().ident.to_string() == "doc" {
+                let mut c = attr.tokens.to_string();
+                c = strip_comment(&mut c).to_string();
+                struct_comments.push(c);
+            }
+        }
+
+        // only process structs with named fields
+        let syn::Fields::Named(named_field) = &struct_item.fields else {
+            continue;
+        };
+        if named_field.named.is_empty() {
+            continue;
+        }
+
+        let struct_name = struct_item.ident.to_string();
+        let mut st = RustStruct {
+            name: struct_name.clone(),
+            fields: vec![],
+            comments: struct_comments,
+        };
+
+        for nt in named_field.named.iter() {
+            let ident = nt.ident.as_ref().unwrap();
+            if !matches!(nt.vis, syn::Visibility::Public(_)) {
+                // skip not-pub fields, but tell the user why
+                sm.warn(
+                    ident.span(),
+                    &format!(
+                        "field `{}` is private and was omitted from the generated Go",
+                        ident
+                    ),
+                );
+                continue;
+            }
+
+            let var_name = ident.to_string();
+            let var_type = match &nt.ty {
+                syn::Type::Path(tp) => tp.path.segments.first().unwrap().ident.to_string(),
+                _ => {
+                    // skip non-path types, but tell the user why
+                    sm.warn(
+                        nt.ty.span(),
+                        &format!(
+                            "field `{}` has an unsupported (non-path) type and was omitted from the generated Go",
+                            ident
+                        ),
+                    );
+                    continue;
                 }
-                // only process struct item
-                match &struct_item.fields {
-                    syn::Fields::Named(named_field) => {
-                        // and structs with named fields
-                        if named_field.named.len() == 0 {
-                            continue;
-                        }
-                        let struct_name = struct_item.ident.to_string();
-                        let mut st = RustStruct {
-                            name: struct_name.clone(),
-                            // struct_item: struct_item.to_owned(),
-                            fields: vec![],
-                            comments: struct_comments,
-                        };
-
-                        for nt in named_field.named.iter() {
-                            match &nt.vis {
-                                syn::Visibility::Public(_) => {}
-                                _ => {
-                                    // skip not-pub fields
-                                    continue;
-                                }
-                            }
-
-                            let var_type;
-                            let var_name = nt.ident.as_ref().unwrap().to_string();
-                            match &nt.ty {
-                                syn::Type::Path(tp) => {
-                                    let sg = tp.path.segments.first().unwrap();
-                                    var_type = sg.ident.to_string();
-                                }
-                                _ => {
-                                    // skip other types
-                                    continue;
-                                }
-                            }
-
-                            // process doc.( start with `///` )
-                            let mut comments = vec![];
-                            for attr in &nt.attrs {
-                                if &attr.path.segments.first().unwrap().ident.to_string() == "doc" {
-                                    let mut c = attr.tokens.to_string();
-                                    c = strip_comment(&mut c).to_string();
-                                    comments.push(c);
-                                }
-                            }
-
-                            let field = StructField {
-                                var_name: var_name,
-                                var_type: var_type,
-                                comments: comments,
-                            };
-                            st.fields.push(field);
-                        }
-                        struct_list.insert(struct_name, st);
-                    }
-                    _ => {}
+            };
+
+            // process doc.( start with `///` )
+            let mut comments = vec![];
+            for attr in &nt.attrs {
+                if &attr.path.segments.first().unwrap().ident.to_string() == "doc" {
+                    let mut c = attr.tokens.to_string();
+                    c = strip_comment(&mut c).to_string();
+                    comments.push(c);
                 }
             }
-            _ => {}
+
+            let field = StructField {
+                var_name,
+                var_type,
+                type_span: nt.ty.span(),
+                comments,
+            };
+            st.fields.push(field);
         }
+        struct_list.insert(struct_name, st);
     }
     struct_list
 }